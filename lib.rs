@@ -11,6 +11,10 @@
 //! hgl assumes GL 3.1 core profile with GLSL 140. It attempts to do complete
 //! error checking, and return the information the GL exposes.
 //!
+//! `ShaderType::TessControlShader`/`TessEvalShader` require GL 4.0, and
+//! `ShaderType::ComputeShader`/`Program::dispatch_compute` require GL 4.3;
+//! using them against an older context fails at shader compile/link time.
+//!
 //! *NOTE*: The various `activate` methods will explicitly bind the object,
 //! but the other methods frequently bind themselves too! Be careful what you
 //! call if you expect something to be bound to stay bound. They do not
@@ -18,28 +22,81 @@
 
 extern mod gl;
 
-use gl::types::{GLint, GLuint, GLenum, GLsizei, GLchar, GLsizeiptr};
+use gl::types::{GLint, GLuint, GLenum, GLsizei, GLchar, GLsizeiptr, GLboolean};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::libc::c_void;
 
+/// Drain every pending error from `glGetError`, returning every code the
+/// driver had queued up (drivers may report more than one per call)
+/// rather than just the first.
+fn check_gl_error(ctx: &str) -> Result<(), ~[GLenum]> {
+    let mut errors = ~[];
+    loop {
+        match gl::GetError() {
+            gl::NO_ERROR => break,
+            err => errors.push(err)
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Check for, and report, any GL error left over from the operation named
+/// by `ctx`. Compiled out entirely unless debug assertions are enabled,
+/// so it is safe to sprinkle after every wrapped GL call; use the public
+/// `check_error` if you want the same check in release builds.
+fn debug_check(ctx: &str) {
+    if cfg!(not(ndebug)) {
+        match check_gl_error(ctx) {
+            Ok(()) => (),
+            Err(errors) => fail!("GL error(s) after {}: {:?}", ctx, errors)
+        }
+    }
+}
+
+/// Drain and return every pending `glGetError` code. Unlike the crate's
+/// internal debug checks, this runs in release builds too, so callers can
+/// insert their own manual checkpoints wherever they suspect trouble.
+pub fn check_error() -> Result<(), ~[GLenum]> {
+    check_gl_error("check_error")
+}
+
 /// Shader types
 pub enum ShaderType {
     VertexShader,
     FragmentShader,
+    GeometryShader,
+    TessControlShader,
+    TessEvalShader,
+    ComputeShader,
 }
 
 impl ShaderType {
     /// Convert a ShaderType into its corresponding GL value
     fn to_glenum(&self) -> GLenum {
         match *self {
-            VertexShader => gl::VERTEX_SHADER,
-            FragmentShader => gl::FRAGMENT_SHADER,
+            VertexShader      => gl::VERTEX_SHADER,
+            FragmentShader    => gl::FRAGMENT_SHADER,
+            GeometryShader    => gl::GEOMETRY_SHADER,
+            TessControlShader => gl::TESS_CONTROL_SHADER,
+            TessEvalShader    => gl::TESS_EVALUATION_SHADER,
+            ComputeShader     => gl::COMPUTE_SHADER,
         }
     }
 }
 
 pub struct Shader {
     priv name: GLuint,
-    priv type_: ShaderType
+    priv type_: ShaderType,
+    // Raw pointers are neither Send nor Sync, so this field's only job is
+    // to stop a Shader from being moved to another task and dropped
+    // there, where glDeleteShader would run against the wrong (or no) GL
+    // context.
+    priv no_send: *const u8
 }
 
 fn get_info_log(shader: GLuint, get: unsafe fn(GLuint, GLenum, *mut GLint),
@@ -83,7 +140,7 @@ impl Shader {
     }
 
     fn new_raw(id: GLuint, type_: ShaderType) -> Shader {
-        Shader { name: id, type_: type_ }
+        Shader { name: id, type_: type_, no_send: 0 as *const u8 }
     }
 
     /// Returns the name (id) of the shader.
@@ -120,7 +177,11 @@ impl Drop for Shader {
 
 /// A program, which consists of multiple compiled shaders "linked" together
 pub struct Program {
-    name: GLuint
+    name: GLuint,
+    priv attrib_locations: RefCell<HashMap<~str, GLint>>,
+    priv uniform_locations: RefCell<HashMap<~str, GLint>>,
+    // See the note on Shader's `no_send` field.
+    priv no_send: *const u8
 }
 
 impl Program {
@@ -135,7 +196,61 @@ impl Program {
 
         match get_info_log(program, gl::GetProgramiv, gl::GetProgramInfoLog, gl::LINK_STATUS) {
             Some(s) => Err(std::str::from_utf8_owned(s).expect("non-utf8 infolog!")),
-            None    => Ok(Program { name: program })
+            None    => Ok(Program {
+                name: program,
+                attrib_locations: RefCell::new(HashMap::new()),
+                uniform_locations: RefCell::new(HashMap::new()),
+                no_send: 0 as *const u8
+            })
+        }
+    }
+
+    /// Look up the location of the vertex attribute `name` in this
+    /// program, consulting the attribute location cache before falling
+    /// back to `glGetAttribLocation`.
+    fn attrib_location(&self, name: &str) -> GLint {
+        match self.attrib_locations.borrow().find_equiv(&name) {
+            Some(&loc) => return loc,
+            None => ()
+        }
+        let loc = name.with_c_str(|cstr| unsafe {
+            gl::GetAttribLocation(self.name, cstr)
+        });
+        self.attrib_locations.borrow_mut().insert(name.to_owned(), loc);
+        loc
+    }
+
+    /// Pre-warm the attribute and uniform location caches by enumerating
+    /// every active attribute and uniform in this program, rather than
+    /// waiting for the first `enable_attrib`/`set_uniform_*` call to miss.
+    pub fn warm_location_cache(&self) {
+        self.warm_cache(gl::ACTIVE_ATTRIBUTES, gl::GetActiveAttrib, gl::GetAttribLocation,
+                        &self.attrib_locations);
+        self.warm_cache(gl::ACTIVE_UNIFORMS, gl::GetActiveUniform, gl::GetUniformLocation,
+                        &self.uniform_locations);
+    }
+
+    fn warm_cache(&self, count_param: GLenum,
+                 get_active: unsafe fn(GLuint, GLuint, GLsizei, *mut GLsizei, *mut GLint,
+                                       *mut GLenum, *mut GLchar),
+                 get_location: unsafe fn(GLuint, *GLchar) -> GLint,
+                 cache: &RefCell<HashMap<~str, GLint>>) {
+        let mut count: GLint = 0;
+        unsafe { gl::GetProgramiv(self.name, count_param, &mut count); }
+
+        let mut name_buf = std::vec::from_elem(256u, 0u8);
+        for i in range(0, count as GLuint) {
+            let mut len: GLsizei = 0;
+            let mut size: GLint = 0;
+            let mut gltype: GLenum = 0;
+            unsafe {
+                get_active(self.name, i, name_buf.len() as GLsizei, &mut len, &mut size,
+                          &mut gltype, name_buf.as_mut_ptr() as *mut GLchar);
+            }
+            let name = std::str::from_utf8(name_buf.slice_to(len as uint))
+                .expect("non-utf8 active variable name!").to_owned();
+            let loc = name.with_c_str(|cstr| unsafe { get_location(self.name, cstr) });
+            cache.borrow_mut().insert(name, loc);
         }
     }
 
@@ -148,11 +263,170 @@ impl Program {
             gl::BindFragDataLocation(self.name, color_number, cstr)
         });
     }
+
+    /// Look up the location of the uniform `name` in this program,
+    /// consulting the uniform location cache before falling back to
+    /// `glGetUniformLocation`. Returns `None` if there is no such active
+    /// uniform (i.e. it returned `-1`), rather than making callers
+    /// special-case that sentinel themselves.
+    pub fn uniform_location(&self, name: &str) -> Option<GLint> {
+        let cached = match self.uniform_locations.borrow().find_equiv(&name) {
+            Some(&loc) => Some(loc),
+            None => None
+        };
+        let loc = match cached {
+            Some(loc) => loc,
+            None => {
+                let loc = name.with_c_str(|cstr| unsafe {
+                    gl::GetUniformLocation(self.name, cstr)
+                });
+                self.uniform_locations.borrow_mut().insert(name.to_owned(), loc);
+                loc
+            }
+        };
+        if loc == -1 { None } else { Some(loc) }
+    }
+
+    /// Set a single-float uniform. Does nothing if `name` is not an active
+    /// uniform of this program. Activates this program first, since
+    /// `glUniform*` always writes to whatever program is currently bound.
+    pub fn set_uniform_1f(&self, name: &str, x: f32) {
+        self.activate();
+        match self.uniform_location(name) {
+            Some(loc) => gl::Uniform1f(loc, x),
+            None => ()
+        }
+    }
+
+    /// Set a 3-float (e.g. `vec3`) uniform. Does nothing if `name` is not
+    /// an active uniform of this program. Activates this program first,
+    /// since `glUniform*` always writes to whatever program is currently
+    /// bound.
+    pub fn set_uniform_3f(&self, name: &str, x: f32, y: f32, z: f32) {
+        self.activate();
+        match self.uniform_location(name) {
+            Some(loc) => gl::Uniform3f(loc, x, y, z),
+            None => ()
+        }
+    }
+
+    /// Set a 4x4 matrix (e.g. `mat4`) uniform from 16 floats, given in
+    /// column-major order unless `transpose` is true. Does nothing if
+    /// `name` is not an active uniform of this program. Activates this
+    /// program first, since `glUniform*` always writes to whatever
+    /// program is currently bound.
+    pub fn set_uniform_matrix4fv(&self, name: &str, transpose: bool, value: &[f32, ..16]) {
+        self.activate();
+        match self.uniform_location(name) {
+            Some(loc) => unsafe {
+                gl::UniformMatrix4fv(loc, 1, transpose as GLboolean, value.as_ptr());
+            },
+            None => ()
+        }
+    }
+
+    /// Push every uniform described by `uniforms` to this program in one
+    /// call. Activates this program first, since `glUniform*` always
+    /// writes to whatever program is currently bound, then calls
+    /// `uniforms.apply(self)`; it does no location lookups of its own.
+    pub fn set_uniforms<U: Uniforms>(&self, uniforms: &U) {
+        self.activate();
+        uniforms.apply(self);
+    }
+
+    /// Launch this program's compute shader over a `x` by `y` by `z` grid
+    /// of work groups. The program must already be activated and must
+    /// have been linked from a `ComputeShader`.
+    pub fn dispatch_compute(&self, x: GLuint, y: GLuint, z: GLuint) {
+        gl::DispatchCompute(x, y, z);
+        debug_check("Program::dispatch_compute DispatchCompute");
+    }
+}
+
+/// Implemented by a user-defined struct holding a frame's worth of uniform
+/// values, so that they can all be pushed to a `Program` in one call via
+/// `Program::set_uniforms`.
+pub trait Uniforms {
+    /// Push this value's uniforms into `program`, which must already be
+    /// activated.
+    fn apply(&self, program: &Program);
+}
+
+/// The scalar type of a single vertex attribute component, as passed to
+/// `glVertexAttribPointer`/`glVertexAttribIPointer`. The integer variants
+/// carry whether the data should be normalized into `[0, 1]`/`[-1, 1]`
+/// (true) or left as-is and read as an integer in the shader (false).
+pub enum AttributeType {
+    F32,
+    I8(bool),
+    U8(bool),
+    I16(bool),
+    U16(bool),
+    I32(bool),
+    U32(bool)
+}
+
+impl AttributeType {
+    fn to_glenum(&self) -> GLenum {
+        match *self {
+            F32     => gl::FLOAT,
+            I8(_)   => gl::BYTE,
+            U8(_)   => gl::UNSIGNED_BYTE,
+            I16(_)  => gl::SHORT,
+            U16(_)  => gl::UNSIGNED_SHORT,
+            I32(_)  => gl::INT,
+            U32(_)  => gl::UNSIGNED_INT,
+        }
+    }
+
+    fn normalized(&self) -> GLboolean {
+        let n = match *self {
+            F32 => false,
+            I8(n) | U8(n) | I16(n) | U16(n) | I32(n) | U32(n) => n
+        };
+        if n { gl::TRUE } else { gl::FALSE }
+    }
+
+    /// Whether this attribute should be read as an integer in the shader
+    /// (`glVertexAttribIPointer`) rather than converted to a float
+    /// (`glVertexAttribPointer`). Normalized integers are always
+    /// converted to float, so they are not "integer" in this sense.
+    fn is_integer(&self) -> bool {
+        match *self {
+            F32 => false,
+            _ => self.normalized() == gl::FALSE
+        }
+    }
+}
+
+/// A single field of an interleaved vertex layout: the attribute name as
+/// it appears in the shader, its scalar type and component count, and its
+/// byte offset within one vertex.
+pub struct VertexAttrib {
+    name: ~str,
+    type_: AttributeType,
+    elts: GLint,
+    offset: uint
+}
+
+/// An ordered description of an interleaved vertex layout, used to enable
+/// every attribute of a vertex struct against a VAO in one call via
+/// `Vao::bind_vertex_format`.
+pub struct VertexFormat {
+    attribs: ~[VertexAttrib]
+}
+
+impl VertexFormat {
+    pub fn new(attribs: ~[VertexAttrib]) -> VertexFormat {
+        VertexFormat { attribs: attribs }
+    }
 }
 
 /// A vertex buffer object
 pub struct Vbo {
-    name: GLuint
+    name: GLuint,
+    // See the note on Shader's `no_send` field.
+    priv no_send: *const u8
 }
 
 impl Drop for Vbo {
@@ -194,8 +468,8 @@ impl Vbo {
                            (data.len() * std::mem::size_of::<T>()) as GLsizeiptr,
                            data.as_ptr() as *c_void, usage.to_glenum());
         }
-        // TODO: check BufferData error
-        Ok(Vbo { name: vbo })
+        debug_check("Vbo::from_data BufferData");
+        Ok(Vbo { name: vbo, no_send: 0 as *const u8 })
     }
 
     pub fn activate(&self) {
@@ -203,9 +477,70 @@ impl Vbo {
     }
 }
 
+/// The element type stored in an `Ebo`, recorded so `Vao::draw_elements`
+/// can pass the right type to `glDrawElements`.
+pub enum IndexType {
+    Indices16,
+    Indices32
+}
+
+impl IndexType {
+    fn to_glenum(&self) -> GLenum {
+        match *self {
+            Indices16 => gl::UNSIGNED_SHORT,
+            Indices32 => gl::UNSIGNED_INT,
+        }
+    }
+}
+
+/// An element (index) buffer object
+pub struct Ebo {
+    name: GLuint,
+    priv type_: IndexType,
+    // See the note on Shader's `no_send` field.
+    priv no_send: *const u8
+}
+
+impl Drop for Ebo {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteBuffers(1, &self.name); }
+    }
+}
+
+impl Ebo {
+    /// Generate a new EBO and upload 16-bit indices to it.
+    pub fn from_u16_data(data: &[u16], usage: VboUsage) -> Result<Ebo, ~str> {
+        Ebo::from_data_raw(data, usage, Indices16)
+    }
+
+    /// Generate a new EBO and upload 32-bit indices to it.
+    pub fn from_u32_data(data: &[u32], usage: VboUsage) -> Result<Ebo, ~str> {
+        Ebo::from_data_raw(data, usage, Indices32)
+    }
+
+    fn from_data_raw<T>(data: &[T], usage: VboUsage, type_: IndexType) -> Result<Ebo, ~str> {
+        let mut ebo: GLuint = 0;
+        unsafe { gl::GenBuffers(1, &mut ebo as *mut GLuint); }
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
+        unsafe {
+            gl::BufferData(gl::ELEMENT_ARRAY_BUFFER,
+                           (data.len() * std::mem::size_of::<T>()) as GLsizeiptr,
+                           data.as_ptr() as *c_void, usage.to_glenum());
+        }
+        debug_check("Ebo::from_data_raw BufferData");
+        Ok(Ebo { name: ebo, type_: type_, no_send: 0 as *const u8 })
+    }
+
+    pub fn activate(&self) {
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.name);
+    }
+}
+
 /// A vertex array object
 pub struct Vao {
-    name: GLuint
+    name: GLuint,
+    // See the note on Shader's `no_send` field.
+    priv no_send: *const u8
 }
 
 impl Drop for Vao {
@@ -218,7 +553,7 @@ impl Vao {
     pub fn new() -> Vao {
         let mut vao: GLuint = 0;
         unsafe { gl::GenVertexArrays(1, &mut vao as *mut GLuint); }
-        Vao { name: vao }
+        Vao { name: vao, no_send: 0 as *const u8 }
     }
 
     pub fn activate(&self) {
@@ -226,25 +561,48 @@ impl Vao {
     }
 
     /// Define and enable an array of generic vertex attribute data for `name`
-    /// in `program`, using this VAO. TODO: Currently hardcoded to GL_FLOAT.
-    /// TODO: Normalize hardcoded to GL_FALSE.
-    pub fn enable_attrib(&self, program: &Program, name: &str, elts: GLint,
-                         stride: GLint, offset: uint) {
+    /// in `program`, using this VAO.
+    pub fn enable_attrib(&self, program: &Program, name: &str, attr_type: AttributeType,
+                         elts: GLint, stride: GLint, offset: uint) {
         self.activate();
-        name.with_c_str(|cstr| {
-            unsafe {
-                let pos = gl::GetAttribLocation(program.name, cstr);
-                gl::EnableVertexAttribArray(pos as GLuint);
-                gl::VertexAttribPointer(pos as GLuint, elts, gl::FLOAT,
-                                        gl::FALSE, stride, offset as *c_void);
+        let pos = program.attrib_location(name);
+        unsafe {
+            gl::EnableVertexAttribArray(pos as GLuint);
+            if attr_type.is_integer() {
+                gl::VertexAttribIPointer(pos as GLuint, elts, attr_type.to_glenum(),
+                                         stride, offset as *c_void);
+            } else {
+                gl::VertexAttribPointer(pos as GLuint, elts, attr_type.to_glenum(),
+                                        attr_type.normalized(), stride, offset as *c_void);
             }
-        });
+        }
+        debug_check("Vao::enable_attrib VertexAttribPointer");
+    }
+
+    /// Enable every attribute described by `format`, for interleaved
+    /// vertices of `stride` bytes in the currently-bound VBO.
+    pub fn bind_vertex_format(&self, program: &Program, format: &VertexFormat, stride: GLint) {
+        for attrib in format.attribs.iter() {
+            self.enable_attrib(program, attrib.name.as_slice(), attrib.type_, attrib.elts, stride,
+                               attrib.offset);
+        }
     }
 
     /// Draw the given primitive, using `count` vertices starting at offset
     /// `first` in the currently bound VBO.
     pub fn draw(&self, primitive: Primitive, first: GLint, count: GLsizei) {
         gl::DrawArrays(primitive.to_glenum(), first, count);
+        debug_check("Vao::draw DrawArrays");
+    }
+
+    /// Draw the given primitive, reading `count` indices from the start of
+    /// `ebo` to index into the currently bound VBO.
+    pub fn draw_elements(&self, primitive: Primitive, ebo: &Ebo, count: GLsizei) {
+        ebo.activate();
+        unsafe {
+            gl::DrawElements(primitive.to_glenum(), count, ebo.type_.to_glenum(), std::ptr::null());
+        }
+        debug_check("Vao::draw_elements DrawElements");
     }
 }
 
@@ -277,3 +635,104 @@ impl Drop for Program {
         gl::DeleteProgram(self.name);
     }
 }
+
+/// Pixel format of a `Texture`'s image data, mapping to the internal
+/// format, upload format, and component type `glTexImage2D` expects.
+pub enum TextureFormat {
+    R8,
+    RGB8,
+    RGBA8
+}
+
+impl TextureFormat {
+    fn to_glenums(&self) -> (GLint, GLenum, GLenum) {
+        match *self {
+            R8    => (gl::R8 as GLint, gl::RED, gl::UNSIGNED_BYTE),
+            RGB8  => (gl::RGB8 as GLint, gl::RGB, gl::UNSIGNED_BYTE),
+            RGBA8 => (gl::RGBA8 as GLint, gl::RGBA, gl::UNSIGNED_BYTE),
+        }
+    }
+}
+
+/// Texture minification/magnification filter.
+pub enum TextureFilter {
+    Nearest,
+    Linear
+}
+
+impl TextureFilter {
+    fn to_glenum(&self) -> GLint {
+        match *self {
+            Nearest => gl::NEAREST as GLint,
+            Linear  => gl::LINEAR as GLint,
+        }
+    }
+}
+
+/// Texture wrap mode, used for both the S and T coordinates.
+pub enum TextureWrap {
+    Repeat,
+    ClampToEdge,
+    MirroredRepeat
+}
+
+impl TextureWrap {
+    fn to_glenum(&self) -> GLint {
+        match *self {
+            Repeat         => gl::REPEAT as GLint,
+            ClampToEdge    => gl::CLAMP_TO_EDGE as GLint,
+            MirroredRepeat => gl::MIRRORED_REPEAT as GLint,
+        }
+    }
+}
+
+/// A 2D texture object
+pub struct Texture {
+    name: GLuint,
+    // See the note on Shader's `no_send` field.
+    priv no_send: *const u8
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteTextures(1, &self.name); }
+    }
+}
+
+impl Texture {
+    /// Generate a new texture and upload `data` as a `width` by `height`
+    /// image of the given `format`, with the given min/mag filters and S/T
+    /// wrap modes.
+    pub fn from_data(width: GLsizei, height: GLsizei, format: TextureFormat, data: &[u8],
+                     min_filter: TextureFilter, mag_filter: TextureFilter,
+                     wrap_s: TextureWrap, wrap_t: TextureWrap) -> Result<Texture, ~str> {
+        let mut name: GLuint = 0;
+        unsafe { gl::GenTextures(1, &mut name as *mut GLuint); }
+        gl::BindTexture(gl::TEXTURE_2D, name);
+
+        let (internal, pixel_format, pixel_type) = format.to_glenums();
+        unsafe {
+            // Rows are tightly packed, with no padding to the default
+            // 4-byte alignment; without this, non-4-byte-aligned rows
+            // (e.g. R8/RGB8 with a width not a multiple of 4) get read
+            // with the wrong stride and come out skewed.
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, internal, width, height, 0, pixel_format,
+                          pixel_type, data.as_ptr() as *c_void);
+        }
+        debug_check("Texture::from_data TexImage2D");
+
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter.to_glenum());
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, mag_filter.to_glenum());
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, wrap_s.to_glenum());
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, wrap_t.to_glenum());
+
+        Ok(Texture { name: name, no_send: 0 as *const u8 })
+    }
+
+    /// Bind this texture to texture unit `unit`, i.e. `GL_TEXTURE0 + unit`.
+    pub fn activate(&self, unit: GLuint) {
+        gl::ActiveTexture(gl::TEXTURE0 + unit);
+        gl::BindTexture(gl::TEXTURE_2D, self.name);
+    }
+}